@@ -28,11 +28,18 @@
 //! Thus, before executing a contract it should be reinstrument with new schedule.
 
 use crate::{
-	CodeHash, CodeStorage, PristineCode, Schedule, Config, Error,
+	BalanceOf, CodeHash, CodeStorage, OwnerInfoOf, PristineCode, Schedule, Config, Error,
 	wasm::{prepare, PrefabWasmModule},
 };
+use crate::gas::{GasMeter, Token};
 use sp_core::crypto::UncheckedFrom;
-use frame_support::{StorageMap, dispatch::DispatchError};
+use sp_runtime::traits::Saturating;
+use frame_support::{
+	StorageMap, ensure,
+	dispatch::{DispatchError, DispatchResult},
+	traits::{Currency, ReservableCurrency},
+	weights::Weight,
+};
 
 /// Put the instrumented module in storage.
 ///
@@ -60,6 +67,44 @@ where
 	code_hash
 }
 
+/// Instrument and store a piece of code without instantiating a contract from it.
+///
+/// Unlike [`store`] — which only ever lands code in state as a side effect of a successful
+/// instantiation — this publishes the code on its own so that it can later be instantiated
+/// many times by its `code_hash`. The caller (`who`) becomes the depositor: a balance
+/// proportional to the length of the pristine code is reserved from their free balance and
+/// held for as long as the code occupies state. The deposit is released by [`remove`] once
+/// the last contract referencing the code is terminated.
+pub fn upload<T: Config>(
+	who: &T::AccountId,
+	original_code: Vec<u8>,
+	schedule: &Schedule<T>,
+) -> Result<CodeHash<T>, DispatchError>
+where
+	T::AccountId: UncheckedFrom<T::Hash> + AsRef<[u8]>
+{
+	let deposit = deposit_for::<T>(original_code.len() as u32);
+	// Instrument and validate the code *before* touching any balance, so that a rejected
+	// module leaves no reservation behind and the failure path stays self-contained.
+	let prefab_module = prepare::prepare_contract::<T>(original_code, schedule)?;
+	let code_hash = prefab_module.code_hash;
+	// Publishing the same code twice would increment the refcount through `store` but orphan
+	// the first depositor's reservation, since only a single owner can be recorded per hash.
+	// Reject the duplicate instead and leave the existing deposit untouched.
+	ensure!(!<CodeStorage<T>>::contains_key(&code_hash), Error::<T>::CodeAlreadyExists);
+	T::Currency::reserve(who, deposit).map_err(|_| Error::<T>::StorageDepositNotEnoughFunds)?;
+	store(prefab_module);
+	// Record who paid for the bytes and how much was reserved so that the reservation can be
+	// returned verbatim when the code is eventually reclaimed.
+	<OwnerInfoOf<T>>::insert(&code_hash, (who.clone(), deposit));
+	Ok(code_hash)
+}
+
+/// The storage deposit reserved for publishing `code_len` bytes of pristine code.
+fn deposit_for<T: Config>(code_len: u32) -> BalanceOf<T> {
+	T::DepositPerByte::get().saturating_mul(code_len.into())
+}
+
 /// Prepare and save the code to storage in one go.
 //
 /// This version neither checks nor instruments the passed in code. This is useful
@@ -81,21 +126,110 @@ pub fn prepare_and_store_unchecked<T: Config>(
 	Ok(code_hash)
 }
 
+/// Decrement the refcount of a stored code and remove it once it reaches zero.
+///
+/// This is the inverse operation to [`store`]. It is called from the contract termination
+/// path so that code which is no longer referenced by any live contract is reclaimed from
+/// storage: both the [`CodeStorage`] and the [`PristineCode`] entry are removed when the last
+/// reference is dropped.
+///
+/// Returns [`Error::CodeNotFound`] if no module is stored under `code_hash`. Decrementing is
+/// saturating, so a refcount that is already at its floor will simply remove the code rather
+/// than underflow.
+///
+/// When the last reference is dropped the storage deposit recorded by [`upload`] is returned
+/// to the depositor before the `OwnerInfoOf` entry is cleared.
+pub fn remove<T: Config>(code_hash: &CodeHash<T>) -> DispatchResult
+where
+	T::AccountId: UncheckedFrom<T::Hash> + AsRef<[u8]>
+{
+	<CodeStorage<T>>::try_mutate_exists(code_hash, |existing| {
+		if let Some(module) = existing {
+			// The decrement is the inverse of the increment in [`store`]. It saturates, so a
+			// `refcount` that is already `0` stays there instead of underflowing. Once the
+			// count reaches `0` both the instrumented and the pristine copy are purged and the
+			// storage deposit is returned to its depositor.
+			module.refcount = module.refcount.saturating_sub(1);
+			if module.refcount == 0 {
+				<PristineCode<T>>::remove(code_hash);
+				if let Some((depositor, deposit)) = <OwnerInfoOf<T>>::take(code_hash) {
+					T::Currency::unreserve(&depositor, deposit);
+				}
+				*existing = None;
+			}
+			Ok(())
+		} else {
+			Err(Error::<T>::CodeNotFound.into())
+		}
+	})
+}
+
+/// The token charged for a lazy re-instrumentation pass triggered from [`load`].
+///
+/// The cost is split into a per-byte component (the whole pristine code is parsed and
+/// rewritten) and a per-metering-point component (each injected gas charge widens the code
+/// and costs extra to emit). Both coefficients live on the [`Schedule`] so they stay
+/// tunable through governance.
+#[cfg_attr(test, derive(Debug, PartialEq, Eq))]
+#[derive(Clone, Copy)]
+struct InstrumentToken {
+	/// Length of the pristine code that was re-instrumented.
+	code_len: u32,
+	/// Number of bytes the instrumentation pass added to the code, used in place of the
+	/// number of injected gas metering points.
+	metering_points: u32,
+}
+
+impl InstrumentToken {
+	/// Derive the token from a freshly prepared module and the length of its pristine source.
+	///
+	/// The number of injected metering points is not stored on the module, so the token uses
+	/// the growth of the code instead: the length of the prepared code minus the length of
+	/// the pristine code, in bytes (zero if the prepared code is not longer). This is a byte
+	/// count, not an exact count of the injected metering points.
+	fn from_prepared<T: Config>(module: &PrefabWasmModule<T>, original_code_len: u32) -> Self {
+		InstrumentToken {
+			code_len: original_code_len,
+			metering_points: (module.code.len() as u32).saturating_sub(original_code_len),
+		}
+	}
+}
+
+impl<T: Config> Token<T> for InstrumentToken {
+	type Metadata = Schedule<T>;
+
+	fn calculate_amount(&self, schedule: &Schedule<T>) -> Weight {
+		schedule.instruction_weights.reinstrument_per_byte
+			.saturating_mul(self.code_len.into())
+			.saturating_add(
+				schedule.instruction_weights.reinstrument_per_metering_point
+					.saturating_mul(self.metering_points.into())
+			)
+	}
+}
+
 /// Load code with the given code hash.
 ///
 /// If the module was instrumented with a lower version of schedule than
 /// the current one given as an argument, then this function will perform
 /// re-instrumentation and update the cache in the storage.
+///
+/// The re-instrumentation pass is not free: its cost is charged against the supplied
+/// `gas_meter` so that the first caller after a schedule upgrade pays for the work instead of
+/// subsidizing everyone that comes after it. The weight actually consumed (zero when the
+/// cached module is already up to date) is returned alongside the module.
 pub fn load<T: Config>(
 	code_hash: CodeHash<T>,
 	schedule: &Schedule<T>,
-) -> Result<PrefabWasmModule<T>, DispatchError>
+	gas_meter: &mut GasMeter<T>,
+) -> Result<(PrefabWasmModule<T>, Weight), DispatchError>
 where
 	T::AccountId: UncheckedFrom<T::Hash> + AsRef<[u8]>
 {
 	let mut prefab_module = <CodeStorage<T>>::get(code_hash)
 		.ok_or_else(|| Error::<T>::CodeNotFound)?;
 
+	let mut consumed = 0;
 	if prefab_module.schedule_version < schedule.version {
 		// The current schedule version is greater than the version of the one cached
 		// in the storage.
@@ -103,9 +237,36 @@ where
 		// We need to re-instrument the code with the latest schedule here.
 		let original_code = <PristineCode<T>>::get(code_hash)
 			.ok_or_else(|| Error::<T>::CodeNotFound)?;
+		let original_code_len = original_code.len() as u32;
 		prefab_module = prepare::prepare_contract::<T>(original_code, schedule)?;
+		// The caller has to pay for the re-instrumentation it triggered. The cost is
+		// measured before writing the fresh module back to the cache, with the code growth of
+		// the prepared module standing in for the number of injected metering points.
+		let token = InstrumentToken::from_prepared(&prefab_module, original_code_len);
+		consumed = gas_meter.charge(schedule, token)?.peek();
 		<CodeStorage<T>>::insert(&code_hash, &prefab_module);
 	}
 	prefab_module.code_hash = code_hash;
+	Ok((prefab_module, consumed))
+}
+
+/// Re-instrument the code stored under `code_hash` against `schedule` without mutating storage.
+///
+/// This is the read-only counterpart to [`load`]: it fetches the [`PristineCode`], runs it
+/// through `prepare::prepare_contract` and hands back the resulting module — including its
+/// injected gas-metering points and memory limits — but never writes the fresh module back to
+/// [`CodeStorage`]. It lets off-chain tooling (and an RPC built on top of it) preview what a
+/// contract would look like under a candidate `Schedule` without committing to the result.
+pub fn instrument_dry_run<T: Config>(
+	code_hash: CodeHash<T>,
+	schedule: &Schedule<T>,
+) -> Result<PrefabWasmModule<T>, DispatchError>
+where
+	T::AccountId: UncheckedFrom<T::Hash> + AsRef<[u8]>
+{
+	let original_code = <PristineCode<T>>::get(code_hash)
+		.ok_or_else(|| Error::<T>::CodeNotFound)?;
+	let mut prefab_module = prepare::prepare_contract::<T>(original_code, schedule)?;
+	prefab_module.code_hash = code_hash;
 	Ok(prefab_module)
 }